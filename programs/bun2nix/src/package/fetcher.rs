@@ -1,16 +1,24 @@
 //! This module holds the implementation for data about a given nix fetcher type
 
-use std::{fmt::Debug, hash::Hash};
+use std::hash::Hash;
 
 use askama::Template;
 use serde::{Deserialize, Serialize};
+use url::Url;
 
 use crate::{
     Options,
     error::{Error, Result},
 };
 
-#[derive(Template, Debug, Serialize, Deserialize, Clone, Eq, Ord, PartialEq, PartialOrd, Hash)]
+mod hash;
+mod prefetch;
+mod registry;
+
+pub use hash::Integrity;
+pub use registry::RegistryConfig;
+
+#[derive(Template, Serialize, Deserialize, Clone, Eq, Ord, PartialEq, PartialOrd, Hash)]
 /// # Package Fetcher
 ///
 /// Nix-translated fetcher for a given package
@@ -19,7 +27,7 @@ pub enum Fetcher {
     #[template(path = "fetchurl.nix_template")]
     FetchUrl {
         /// The url to fetch the package from
-        url: String,
+        url: Url,
         /// The hash of the downloaded results
         /// This can be derived from the bun lockfile
         hash: String,
@@ -28,11 +36,11 @@ pub enum Fetcher {
     #[template(path = "fetchgit.nix_template")]
     FetchGit {
         /// The url to fetch the package from
-        url: String,
+        url: Url,
         /// The commit ref to fetch
         rev: String,
         /// The hash of the downloaded results
-        /// This must be calculated via nix-prefetch
+        /// This can be computed with [`Fetcher::prefetch`], or provided directly
         hash: String,
     },
     /// A package which must be retrieved with nix's `pkgs.fetchFromGitHub`
@@ -45,19 +53,42 @@ pub enum Fetcher {
         /// The git ref to fetch
         rev: String,
         /// The hash of the downloaded results
-        /// This must be calculated via nix-prefetch
+        /// This can be computed with [`Fetcher::prefetch`], or provided directly
+        hash: String,
+    },
+    /// A package which must be retrieved with nix's `pkgs.fetchurl`, against
+    /// a private registry that requires authentication
+    ///
+    /// Credentials are supplied via a generated `netrc` file (referenced
+    /// through `curlOptsList`/`netrcPhase`) rather than being inlined into
+    /// the url, so the token never lands in the store path hash
+    #[template(path = "fetchurl-authenticated.nix_template")]
+    FetchUrlAuthenticated {
+        /// The url to fetch the package from
+        url: Url,
+        /// The hash of the downloaded results
+        /// This can be derived from the bun lockfile
         hash: String,
+        /// The registry host the auth token applies to, used as the
+        /// `netrc` `machine`
+        host: String,
+        /// The auth token supplied as the `netrc` `password`
+        token: String,
     },
     /// A package which must be retrieved with nix's `pkgs.fetchtarball`
     #[template(path = "fetchtarball.nix_template")]
     FetchTarball {
         /// The url to fetch the package from
-        url: String,
+        url: Url,
         /// The hash of the downloaded results
-        /// This must be calculated via nix-prefetch
+        /// This can be computed with [`Fetcher::prefetch`], or provided directly
         hash: String,
     },
     /// A package can be a path copied to the store directly
+    ///
+    /// This is also used for dependencies referenced via a `file://` URL or
+    /// a bun `file:` specifier, since those point at a local tarball or
+    /// directory rather than a remote registry
     #[template(path = "copy-to-store.nix_template")]
     CopyToStore {
         /// The path from the root to copy to the store
@@ -65,24 +96,141 @@ pub enum Fetcher {
     },
 }
 
+/// Prefix used by bun lockfiles for the bare `file:` specifier form (e.g.
+/// `file:../local-pkg`, `file:local.tgz`), used for local/workspace
+/// dependencies. This also matches the full `file://` URL form, since that
+/// starts with the same prefix
+const FILE_SPEC_PREFIX: &str = "file:";
+
+/// Resolve a registry path/url that refers to a local file or directory
+/// into the literal filesystem path it refers to. Handles both the full
+/// `file://` URL form and bun's bare `file:` specifier form
+/// (`file:../local-pkg`, `file:local.tgz`) - the latter must be taken as a
+/// literal relative/absolute path rather than parsed as a URL, since
+/// `Url::parse` silently collapses a relative `file:` path down to just its
+/// last segment
+fn local_file_path(spec: &str) -> Option<&str> {
+    spec.strip_prefix("file://")
+        .or_else(|| spec.strip_prefix(FILE_SPEC_PREFIX))
+}
+
 /// The default NPM registry URL
 pub const DEFAULT_REGISTRY: &str = "https://registry.npmjs.org/";
 
+/// Strip the `userinfo` component from a url so that an embedded username or
+/// password never ends up in debug output, logs, or error messages
+fn redact_userinfo(url: &Url) -> Url {
+    let mut redacted = url.clone();
+    let _ = redacted.set_username("");
+    let _ = redacted.set_password(None);
+
+    redacted
+}
+
+impl std::fmt::Debug for Fetcher {
+    /// A hand-written `Debug` impl is used instead of `#[derive(Debug)]` so
+    /// that any username/password embedded in a fetcher's url (e.g. an
+    /// authenticated registry) is redacted before it can leak into logs
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FetchUrl { url, hash } => f
+                .debug_struct("FetchUrl")
+                .field("url", &redact_userinfo(url).as_str())
+                .field("hash", hash)
+                .finish(),
+            Self::FetchGit { url, rev, hash } => f
+                .debug_struct("FetchGit")
+                .field("url", &redact_userinfo(url).as_str())
+                .field("rev", rev)
+                .field("hash", hash)
+                .finish(),
+            Self::FetchGitHub {
+                owner,
+                repo,
+                rev,
+                hash,
+            } => f
+                .debug_struct("FetchGitHub")
+                .field("owner", owner)
+                .field("repo", repo)
+                .field("rev", rev)
+                .field("hash", hash)
+                .finish(),
+            Self::FetchUrlAuthenticated {
+                url,
+                hash,
+                host,
+                token: _,
+            } => f
+                .debug_struct("FetchUrlAuthenticated")
+                .field("url", &redact_userinfo(url).as_str())
+                .field("hash", hash)
+                .field("host", host)
+                .field("token", &"<redacted>")
+                .finish(),
+            Self::FetchTarball { url, hash } => f
+                .debug_struct("FetchTarball")
+                .field("url", &redact_userinfo(url).as_str())
+                .field("hash", hash)
+                .finish(),
+            Self::CopyToStore { path } => f.debug_struct("CopyToStore").field("path", path).finish(),
+        }
+    }
+}
+
 impl Fetcher {
     /// # From NPM Package Name
     ///
     /// Initialize a fetcher from an npm identifier and
-    /// it's hash, optionally using a custom registry path
+    /// it's hash, optionally using a custom registry path and/or a
+    /// per-scope registry config
     ///
     /// ## Arguments
     /// * `ident` - The package identifier (e.g., "@types/node@1.0.0")
-    /// * `hash` - The integrity hash of the package
+    /// * `hash` - The package's npm integrity string (e.g.
+    ///   `sha512-<base64>`), normalized into a Nix-accepted SRI hash
     /// * `registry_path` - Optional registry path from bun.lock. Can be:
-    ///   - None or empty: uses the default npmjs.org registry
+    ///   - None or empty: falls back to `registry_config`, then the default
+    ///     npmjs.org registry
     ///   - Full tarball URL (ends with .tgz): used directly
+    ///   - `file://` URL or bare `file:` specifier: a local tarball or
+    ///     directory, used directly
     ///   - Base registry URL: package path is appended
-    pub fn new_npm_package(ident: &str, hash: String, registry_path: Option<&str>) -> Result<Self> {
-        let url = Self::to_npm_url(ident, registry_path)?;
+    /// * `registry_config` - Optional per-scope registry/auth config parsed
+    ///   from `.npmrc`/`bunfig.toml`. If the resolved registry host has a
+    ///   configured auth token, the token is supplied via a `netrc` file
+    ///   rather than being inlined into the url
+    pub fn new_npm_package(
+        ident: &str,
+        hash: String,
+        registry_path: Option<&str>,
+        registry_config: Option<&RegistryConfig>,
+    ) -> Result<Self> {
+        let url = Self::to_npm_url(ident, registry_path, registry_config)?;
+
+        if let Some(path) = local_file_path(&url) {
+            return Ok(Self::CopyToStore {
+                path: path.to_string(),
+            });
+        }
+
+        let hash = Integrity::parse(&hash)?.to_sri();
+        let url = Url::parse(&url).map_err(Error::InvalidUrl)?;
+
+        let token = url
+            .host_str()
+            .and_then(|host| registry_config.and_then(|config| config.auth_token_for_host(host)));
+
+        if let Some(token) = token {
+            let host = url.host_str().expect("checked above").to_string();
+
+            return Ok(Self::FetchUrlAuthenticated {
+                url,
+                hash,
+                host,
+                token: token.to_string(),
+            });
+        }
 
         Ok(Self::FetchUrl { url, hash })
     }
@@ -93,45 +241,84 @@ impl Fetcher {
     ///
     /// ## Usage
     ///```rust
-    /// use bun2nix::package::Fetcher;
+    /// use bun2nix::package::{Fetcher, RegistryConfig};
     ///
     /// // Default registry
     /// let npm_identifier = "@alloc/quick-lru@5.2.0";
     ///
     /// assert_eq!(
-    ///     Fetcher::to_npm_url(npm_identifier, None).unwrap(),
+    ///     Fetcher::to_npm_url(npm_identifier, None, None).unwrap(),
     ///     "https://registry.npmjs.org/@alloc/quick-lru/-/quick-lru-5.2.0.tgz"
     /// );
     ///
     /// // Custom registry (base URL)
     /// assert_eq!(
-    ///     Fetcher::to_npm_url(npm_identifier, Some("https://npm.pkg.github.com/")).unwrap(),
+    ///     Fetcher::to_npm_url(npm_identifier, Some("https://npm.pkg.github.com/"), None).unwrap(),
     ///     "https://npm.pkg.github.com/@alloc/quick-lru/-/quick-lru-5.2.0.tgz"
     /// );
     ///
     /// // Unscoped package with custom registry
     /// assert_eq!(
-    ///     Fetcher::to_npm_url("lodash@4.17.21", Some("https://npm.example.com")).unwrap(),
+    ///     Fetcher::to_npm_url("lodash@4.17.21", Some("https://npm.example.com"), None).unwrap(),
     ///     "https://npm.example.com/lodash/-/lodash-4.17.21.tgz"
     /// );
     ///
     /// // Full tarball URL (used directly)
     /// assert_eq!(
-    ///     Fetcher::to_npm_url("lodash@4.17.21", Some("https://npm.pkg.github.com/lodash/-/lodash-4.17.21.tgz")).unwrap(),
+    ///     Fetcher::to_npm_url("lodash@4.17.21", Some("https://npm.pkg.github.com/lodash/-/lodash-4.17.21.tgz"), None).unwrap(),
     ///     "https://npm.pkg.github.com/lodash/-/lodash-4.17.21.tgz"
     /// );
+    ///
+    /// // `file://` URL (local tarball or directory, used directly)
+    /// assert_eq!(
+    ///     Fetcher::to_npm_url("lodash@4.17.21", Some("file:///home/user/lodash-4.17.21.tgz"), None).unwrap(),
+    ///     "file:///home/user/lodash-4.17.21.tgz"
+    /// );
+    ///
+    /// // Bare `file:` specifier (a relative local path, used directly)
+    /// assert_eq!(
+    ///     Fetcher::to_npm_url("local-pkg@1.0.0", Some("file:../local-pkg"), None).unwrap(),
+    ///     "file:../local-pkg"
+    /// );
+    ///
+    /// // Scope resolved via a `RegistryConfig`, no explicit registry_path
+    /// let config = RegistryConfig::parse("@mycorp:registry=https://npm.mycorp.dev/\n");
+    ///
+    /// assert_eq!(
+    ///     Fetcher::to_npm_url("@mycorp/utils@1.2.3", None, Some(&config)).unwrap(),
+    ///     "https://npm.mycorp.dev/@mycorp/utils/-/utils-1.2.3.tgz"
+    /// );
     /// ```
-    pub fn to_npm_url(ident: &str, registry_path: Option<&str>) -> Result<String> {
-        // If registry_path is a full tarball URL, use it directly
+    pub fn to_npm_url(
+        ident: &str,
+        registry_path: Option<&str>,
+        registry_config: Option<&RegistryConfig>,
+    ) -> Result<String> {
+        // If registry_path is a full tarball URL or a local `file:`
+        // path/specifier, use it directly rather than constructing a
+        // registry-relative one
         if let Some(path) = registry_path {
-            if !path.is_empty() && path.ends_with(".tgz") {
+            if !path.is_empty() && (path.ends_with(".tgz") || path.starts_with(FILE_SPEC_PREFIX)) {
                 return Ok(path.to_string());
             }
         }
 
-        // Determine the base registry URL
-        let base_url = match registry_path {
-            Some(url) if !url.is_empty() => {
+        // The package's scope (e.g. "@mycorp"), if any, used to look up a
+        // per-scope registry override
+        let scope = ident
+            .strip_prefix('@')
+            .and_then(|rest| rest.split_once('/'))
+            .map(|(scope, _)| format!("@{scope}"));
+
+        // Determine the base registry URL: an explicit registry_path wins,
+        // then a scope-specific registry from registry_config, then the
+        // default npmjs.org registry
+        let scoped_registry = scope
+            .as_deref()
+            .and_then(|scope| registry_config.and_then(|config| config.registry_for_scope(scope)));
+
+        let base_url = match registry_path.filter(|path| !path.is_empty()).or(scoped_registry) {
+            Some(url) => {
                 // Ensure the registry URL ends with a slash
                 if url.ends_with('/') {
                     url.to_string()
@@ -139,7 +326,7 @@ impl Fetcher {
                     format!("{}/", url)
                 }
             }
-            _ => DEFAULT_REGISTRY.to_string(),
+            None => DEFAULT_REGISTRY.to_string(),
         };
 
         // Construct the tarball URL from the package identifier
@@ -0,0 +1,107 @@
+//! Normalizes npm integrity strings (`sha512-<base64>`, and the legacy
+//! `sha1-<base64>` form) into the canonical SRI string nix's `fetchurl`
+//! expects
+
+use base64::Engine;
+
+use crate::error::{Error, Result};
+
+/// Raw digest length, in bytes, expected for each supported algorithm
+const SHA1_DIGEST_LEN: usize = 20;
+const SHA256_DIGEST_LEN: usize = 32;
+const SHA512_DIGEST_LEN: usize = 64;
+
+/// # NPM Integrity
+///
+/// A parsed `<algo>-<base64>` npm integrity string (e.g. a bun lockfile
+/// package's `hash` field), validated and ready to render as the canonical
+/// SRI string nix's `fetchurl` accepts
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Integrity {
+    /// The hash algorithm's canonical lowercase name (`sha1`, `sha256`, or
+    /// `sha512`)
+    algorithm: &'static str,
+    /// The raw, decoded digest bytes
+    digest: Vec<u8>,
+}
+
+impl Integrity {
+    /// # Parse
+    ///
+    /// Parse an npm integrity string, validating that its algorithm is
+    /// supported and that the decoded digest is the expected length for
+    /// that algorithm
+    ///
+    /// ## Usage
+    ///```rust
+    /// use bun2nix::package::Integrity;
+    ///
+    /// let integrity = Integrity::parse(
+    ///     "sha512-MJ7MSJwS1utMxA9QyQLytNDtd+5RGnx6m808qG1M2G+YndNbxf9JlnDaNCVbRbDP2DDoH2Bdz33FVC6TrpzXbw==",
+    /// )
+    /// .unwrap();
+    ///
+    /// assert_eq!(
+    ///     integrity.to_sri(),
+    ///     "sha512-MJ7MSJwS1utMxA9QyQLytNDtd+5RGnx6m808qG1M2G+YndNbxf9JlnDaNCVbRbDP2DDoH2Bdz33FVC6TrpzXbw=="
+    /// );
+    ///
+    /// // Legacy sha1 integrity values are accepted in either base64...
+    /// assert!(Integrity::parse("sha1-Kq5sNclPz7QV2+lfQIuc6R7oRu0=").is_ok());
+    ///
+    /// // ...or hex digest form
+    /// assert!(Integrity::parse("sha1-2aae6c35c94fcfb415dbe95f408b9ce91ee846ed").is_ok());
+    ///
+    /// // Unsupported algorithms and malformed digests are rejected
+    /// assert!(Integrity::parse("md5-deadbeef").is_err());
+    /// assert!(Integrity::parse("sha512-tooshort").is_err());
+    /// ```
+    pub fn parse(integrity: &str) -> Result<Self> {
+        let Some((algorithm, digest_b64)) = integrity.split_once('-') else {
+            return Err(Error::MalformedIntegrity(integrity.to_string()));
+        };
+
+        let (algorithm, expected_len) = match algorithm {
+            "sha1" => ("sha1", SHA1_DIGEST_LEN),
+            "sha256" => ("sha256", SHA256_DIGEST_LEN),
+            "sha512" => ("sha512", SHA512_DIGEST_LEN),
+            other => return Err(Error::UnsupportedIntegrityAlgorithm(other.to_string())),
+        };
+
+        // Most integrity strings carry a base64 digest, but some lockfiles
+        // still carry the legacy `sha1-<hex>` shasum form
+        let digest = if digest_b64.len() == expected_len * 2 && digest_b64.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            decode_hex(digest_b64).map_err(|_| Error::MalformedIntegrity(integrity.to_string()))?
+        } else {
+            base64::engine::general_purpose::STANDARD
+                .decode(digest_b64)
+                .map_err(|_| Error::MalformedIntegrity(integrity.to_string()))?
+        };
+
+        if digest.len() != expected_len {
+            return Err(Error::MalformedIntegrity(integrity.to_string()));
+        }
+
+        Ok(Self { algorithm, digest })
+    }
+
+    /// # To SRI
+    ///
+    /// Render this integrity value as the canonical SRI string nix's
+    /// `fetchurl` accepts
+    pub fn to_sri(&self) -> String {
+        format!(
+            "{}-{}",
+            self.algorithm,
+            base64::engine::general_purpose::STANDARD.encode(&self.digest)
+        )
+    }
+}
+
+/// Decode a lowercase-or-uppercase hex string into raw bytes
+fn decode_hex(hex: &str) -> std::result::Result<Vec<u8>, std::num::ParseIntError> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16))
+        .collect()
+}
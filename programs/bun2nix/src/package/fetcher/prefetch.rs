@@ -0,0 +1,540 @@
+//! Resolves a [`Fetcher`]'s hash by streaming its resource from the network
+//! and hashing it incrementally, mirroring what `nix-prefetch` does by hand
+//! today. The whole download is never buffered in memory at once, and a
+//! download is skipped entirely when the resulting store path already
+//! exists locally
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::error::{Error, Result};
+
+use super::Fetcher;
+
+/// Root of the local nix store, used both to predict a would-be download's
+/// store path and to build the fingerprint nix hashes to produce it
+const NIX_STORE_DIR: &str = "/nix/store";
+
+/// Alphabet nix uses to base32-encode a compressed store path hash
+const NIX_BASE32_ALPHABET: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+impl Fetcher {
+    /// # Prefetch
+    ///
+    /// Resolve this fetcher's hash by streaming its resource and hashing it
+    /// as bytes arrive. If `expected_hash` is given and already predicts a
+    /// store path that exists locally, the download is skipped entirely;
+    /// otherwise the resource is downloaded, hashed, and the result is
+    /// checked against `expected_hash` (erroring on mismatch). A fetcher
+    /// that already carries a hash, or that has nothing to download (e.g.
+    /// [`Fetcher::CopyToStore`]), is returned unchanged
+    pub async fn prefetch(self, expected_hash: Option<&str>) -> Result<Self> {
+        if self.has_hash() {
+            return Ok(self);
+        }
+
+        let Some(url) = self.download_url() else {
+            return Ok(self);
+        };
+
+        if let Some(expected) = expected_hash {
+            let store_path = predicted_store_path(&self.store_name(), expected, self.is_recursive());
+
+            if store_path.exists() {
+                return Ok(self.with_hash(expected.to_string()));
+            }
+        }
+
+        let computed = match &self {
+            // A git remote isn't downloadable as a gzip archive via a bare
+            // GET, and even a host that tolerated that would hand back its
+            // default branch rather than `rev` - so this clones the url and
+            // checks out `rev` directly instead of downloading it as an archive
+            Self::FetchGit { rev, .. } => hash_git_checkout(&url, rev).await?,
+            _ if self.is_recursive() => hash_unpacked(&url).await?,
+            _ => hash_flat(&url, self.auth_header().as_deref()).await?,
+        };
+
+        if let Some(expected) = expected_hash {
+            if expected != computed {
+                return Err(Error::PrefetchHashMismatch {
+                    expected: expected.to_string(),
+                    computed,
+                });
+            }
+        }
+
+        Ok(self.with_hash(computed))
+    }
+
+    /// Whether this fetcher already carries a non-empty hash
+    fn has_hash(&self) -> bool {
+        self.hash().is_some_and(|hash| !hash.is_empty())
+    }
+
+    /// This fetcher's hash field, if it has one (`CopyToStore` has none)
+    fn hash(&self) -> Option<&str> {
+        match self {
+            Self::FetchUrl { hash, .. }
+            | Self::FetchUrlAuthenticated { hash, .. }
+            | Self::FetchGit { hash, .. }
+            | Self::FetchGitHub { hash, .. }
+            | Self::FetchTarball { hash, .. } => Some(hash),
+            Self::CopyToStore { .. } => None,
+        }
+    }
+
+    /// The url this fetcher's resource can be downloaded from, constructing
+    /// one for variants (like [`Fetcher::FetchGitHub`]) that don't store a
+    /// url directly
+    fn download_url(&self) -> Option<Url> {
+        match self {
+            Self::FetchUrl { url, .. }
+            | Self::FetchUrlAuthenticated { url, .. }
+            | Self::FetchGit { url, .. }
+            | Self::FetchTarball { url, .. } => Some(url.clone()),
+            Self::FetchGitHub { owner, repo, rev, .. } => {
+                Url::parse(&format!("https://github.com/{owner}/{repo}/archive/{rev}.tar.gz")).ok()
+            }
+            Self::CopyToStore { .. } => None,
+        }
+    }
+
+    /// The `Authorization` header value to send when downloading this
+    /// fetcher's resource, if it's an authenticated private-registry package
+    fn auth_header(&self) -> Option<String> {
+        match self {
+            Self::FetchUrlAuthenticated { token, .. } => Some(format!("Bearer {token}")),
+            _ => None,
+        }
+    }
+
+    /// Whether nix stores this fetcher's result as a directory, which is
+    /// hashed recursively (a NAR hash) rather than as a single flat file
+    fn is_recursive(&self) -> bool {
+        matches!(
+            self,
+            Self::FetchGit { .. } | Self::FetchGitHub { .. } | Self::FetchTarball { .. }
+        )
+    }
+
+    /// A stable name to use when predicting this fetcher's store path
+    fn store_name(&self) -> String {
+        match self {
+            Self::FetchUrl { url, .. } | Self::FetchUrlAuthenticated { url, .. } | Self::FetchTarball { url, .. } => {
+                url.path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .filter(|segment| !segment.is_empty())
+                    .unwrap_or("source")
+                    .to_string()
+            }
+            Self::FetchGit { .. } => "source".to_string(),
+            Self::FetchGitHub { repo, .. } => repo.clone(),
+            Self::CopyToStore { .. } => "source".to_string(),
+        }
+    }
+
+    /// Return a copy of this fetcher with its hash field set to `hash`
+    fn with_hash(self, hash: String) -> Self {
+        match self {
+            Self::FetchUrl { url, .. } => Self::FetchUrl { url, hash },
+            Self::FetchUrlAuthenticated { url, host, token, .. } => Self::FetchUrlAuthenticated {
+                url,
+                hash,
+                host,
+                token,
+            },
+            Self::FetchGit { url, rev, .. } => Self::FetchGit { url, rev, hash },
+            Self::FetchGitHub { owner, repo, rev, .. } => Self::FetchGitHub {
+                owner,
+                repo,
+                rev,
+                hash,
+            },
+            Self::FetchTarball { url, .. } => Self::FetchTarball { url, hash },
+            Self::CopyToStore { path } => Self::CopyToStore { path },
+        }
+    }
+}
+
+/// Stream `url`'s body and compute its flat SHA-256 as bytes arrive, never
+/// buffering the whole response in memory. `auth` is sent as the request's
+/// `Authorization` header, when the fetcher is an authenticated registry
+async fn hash_flat(url: &Url, auth: Option<&str>) -> Result<String> {
+    let mut request = reqwest::Client::new().get(url.clone());
+
+    if let Some(auth) = auth {
+        request = request.header(reqwest::header::AUTHORIZATION, auth);
+    }
+
+    let mut response = request
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(Error::Prefetch)?;
+
+    let mut hasher = Sha256::new();
+
+    while let Some(chunk) = response.chunk().await.map_err(Error::Prefetch)? {
+        hasher.update(&chunk);
+    }
+
+    Ok(sri_sha256(&hasher.finalize()))
+}
+
+/// Stream `url`'s body to a temporary file, unpack it, and hash the
+/// resulting directory tree the same way nix hashes a NAR
+async fn hash_unpacked(url: &Url) -> Result<String> {
+    let temp_dir = tempfile::tempdir().map_err(Error::Io)?;
+    let archive_path = temp_dir.path().join("source.tar.gz");
+
+    let mut response = reqwest::get(url.clone())
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(Error::Prefetch)?;
+
+    let mut archive_file = tokio::fs::File::create(&archive_path).await.map_err(Error::Io)?;
+
+    while let Some(chunk) = response.chunk().await.map_err(Error::Prefetch)? {
+        archive_file.write_all(&chunk).await.map_err(Error::Io)?;
+    }
+
+    let unpacked_path = temp_dir.path().join("unpacked");
+    std::fs::create_dir(&unpacked_path).map_err(Error::Io)?;
+
+    let hash = tokio::task::spawn_blocking(move || unpack_tarball(&archive_path, &unpacked_path))
+        .await
+        .map_err(|error| Error::Io(std::io::Error::other(error.to_string())))??;
+
+    Ok(hash)
+}
+
+/// Clone `url` at `rev` into a fresh temp directory and hash the resulting
+/// working tree as a NAR, mirroring what `pkgs.fetchgit` produces
+async fn hash_git_checkout(url: &Url, rev: &str) -> Result<String> {
+    let temp_dir = tempfile::tempdir().map_err(Error::Io)?;
+    let checkout_path = temp_dir.path().join("checkout");
+
+    let url = url.clone();
+    let rev = rev.to_string();
+    let checkout_path_for_blocking = checkout_path.clone();
+
+    tokio::task::spawn_blocking(move || clone_and_checkout(&url, &rev, &checkout_path_for_blocking))
+        .await
+        .map_err(|error| Error::Io(std::io::Error::other(error.to_string())))??;
+
+    nar_sha256_dir(&checkout_path)
+}
+
+/// Clone `url` without checking out a working tree, check out `rev`, then
+/// strip `.git` so only the tree contents (what `fetchgit` actually stores)
+/// are left behind
+fn clone_and_checkout(url: &Url, rev: &str, destination: &Path) -> Result<()> {
+    let destination_str = destination.to_string_lossy();
+
+    run_git(&["clone", "--quiet", "--no-checkout", url.as_str(), &destination_str])?;
+    run_git(&["-C", &destination_str, "checkout", "--quiet", rev])?;
+    run_git(&[
+        "-C",
+        &destination_str,
+        "submodule",
+        "update",
+        "--init",
+        "--recursive",
+        "--quiet",
+    ])?;
+
+    let git_dir = destination.join(".git");
+
+    if git_dir.exists() {
+        std::fs::remove_dir_all(&git_dir).map_err(Error::Io)?;
+    }
+
+    Ok(())
+}
+
+/// Run `git` with `args`, erroring if it doesn't exit successfully
+fn run_git(args: &[&str]) -> Result<()> {
+    let status = std::process::Command::new("git")
+        .args(args)
+        .status()
+        .map_err(Error::Io)?;
+
+    if !status.success() {
+        return Err(Error::Io(std::io::Error::other(format!(
+            "git {} failed with {status}",
+            args.join(" ")
+        ))));
+    }
+
+    Ok(())
+}
+
+/// Decompress and unpack a gzipped tarball into `destination`, then return
+/// the SHA-256 of the resulting directory tree. When the archive holds a
+/// single top-level directory, that directory is hashed on its own, as
+/// `fetchFromGitHub`/`fetchTarball` store it
+fn unpack_tarball(archive_path: &Path, destination: &Path) -> Result<String> {
+    let archive_file = std::fs::File::open(archive_path).map_err(Error::Io)?;
+    let decompressed = flate2::read::GzDecoder::new(archive_file);
+
+    tar::Archive::new(decompressed)
+        .unpack(destination)
+        .map_err(Error::Io)?;
+
+    let entries = std::fs::read_dir(destination)
+        .map_err(Error::Io)?
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(Error::Io)?;
+
+    match entries.as_slice() {
+        [entry] if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) => nar_sha256_dir(&entry.path()),
+        _ => nar_sha256_dir(destination),
+    }
+}
+
+/// A `std::io::Write` sink that feeds everything written to it straight into
+/// a running SHA-256, so a NAR never has to be buffered in memory to hash it
+struct HashWriter(Sha256);
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Write one NAR "string": a little-endian u64 length, the bytes
+/// themselves, and zero padding out to the next 8-byte boundary
+fn write_nar_string(writer: &mut impl Write, bytes: &[u8]) -> Result<()> {
+    writer
+        .write_all(&(bytes.len() as u64).to_le_bytes())
+        .map_err(Error::Io)?;
+    writer.write_all(bytes).map_err(Error::Io)?;
+
+    let padding = (8 - bytes.len() % 8) % 8;
+
+    if padding > 0 {
+        writer.write_all(&[0u8; 8][..padding]).map_err(Error::Io)?;
+    }
+
+    Ok(())
+}
+
+/// Recursively write `path` as a NAR node, following the real `nix-archive-1`
+/// grammar: `"(" "type" ("regular" | "directory" | "symlink") ... ")"`, with
+/// directory entries visited in sorted order
+fn write_nar_node(writer: &mut impl Write, path: &Path) -> Result<()> {
+    write_nar_string(writer, b"(")?;
+
+    let metadata = std::fs::symlink_metadata(path).map_err(Error::Io)?;
+
+    if metadata.is_symlink() {
+        let target = std::fs::read_link(path).map_err(Error::Io)?;
+
+        write_nar_string(writer, b"type")?;
+        write_nar_string(writer, b"symlink")?;
+        write_nar_string(writer, b"target")?;
+        write_nar_string(writer, target.to_string_lossy().as_bytes())?;
+    } else if metadata.is_dir() {
+        write_nar_string(writer, b"type")?;
+        write_nar_string(writer, b"directory")?;
+
+        let mut names: Vec<_> = std::fs::read_dir(path)
+            .map_err(Error::Io)?
+            .map(|entry| entry.map(|entry| entry.file_name()).map_err(Error::Io))
+            .collect::<Result<_>>()?;
+        names.sort();
+
+        for name in names {
+            write_nar_string(writer, b"entry")?;
+            write_nar_string(writer, b"(")?;
+            write_nar_string(writer, b"name")?;
+            write_nar_string(writer, name.to_string_lossy().as_bytes())?;
+            write_nar_string(writer, b"node")?;
+            write_nar_node(writer, &path.join(&name))?;
+            write_nar_string(writer, b")")?;
+        }
+    } else {
+        write_nar_string(writer, b"type")?;
+        write_nar_string(writer, b"regular")?;
+
+        if is_executable(&metadata) {
+            write_nar_string(writer, b"executable")?;
+            write_nar_string(writer, b"")?;
+        }
+
+        write_nar_string(writer, b"contents")?;
+        write_nar_string(writer, &std::fs::read(path).map_err(Error::Io)?)?;
+    }
+
+    write_nar_string(writer, b")")?;
+
+    Ok(())
+}
+
+/// Hash a directory tree by serializing it as a real NAR (the same format
+/// `nix-prefetch-git`/`fetchFromGitHub`/`fetchTarball` hash) and feeding the
+/// serialization straight into a running SHA-256
+fn nar_sha256_dir(root: &Path) -> Result<String> {
+    let mut writer = HashWriter(Sha256::new());
+
+    write_nar_string(&mut writer, b"nix-archive-1")?;
+    write_nar_node(&mut writer, root)?;
+
+    Ok(sri_sha256(&writer.0.finalize()))
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// Encode a raw SHA-256 digest as a Nix-accepted SRI string
+fn sri_sha256(digest: &[u8]) -> String {
+    format!(
+        "sha256-{}",
+        base64::engine::general_purpose::STANDARD.encode(digest)
+    )
+}
+
+/// Predict the store path that fetching `name` with the given (already-SRI)
+/// `hash` would produce, so the download can be skipped if it already
+/// exists. Mirrors nix's `makeFixedOutputPath`: the recursive-sha256 case
+/// (git/tarball/github fetchers) hashes a single `source:sha256:...`
+/// fingerprint directly, while the flat case hashes a `fixed:out:...`
+/// fingerprint and then wraps that digest in a second `output:out:...` hash
+fn predicted_store_path(name: &str, sri_hash: &str, recursive: bool) -> PathBuf {
+    let digest_hex = sri_to_hex(sri_hash);
+
+    let store_hash = if recursive {
+        Sha256::digest(format!("source:sha256:{digest_hex}:{NIX_STORE_DIR}:{name}").as_bytes())
+    } else {
+        let inner = Sha256::digest(format!("fixed:out:sha256:{digest_hex}:").as_bytes());
+        let inner_hex = hex_encode(&inner);
+
+        Sha256::digest(format!("output:out:sha256:{inner_hex}:{NIX_STORE_DIR}:{name}").as_bytes())
+    };
+
+    let compressed = compress_hash(&store_hash, 20);
+
+    PathBuf::from(NIX_STORE_DIR).join(format!("{}-{}", base32_encode(&compressed), name))
+}
+
+/// Compress a hash down to `size` bytes by XOR-folding it, as nix does
+/// before base32-encoding a store path hash
+fn compress_hash(hash: &[u8], size: usize) -> Vec<u8> {
+    let mut out = vec![0u8; size];
+
+    for (index, byte) in hash.iter().enumerate() {
+        out[index % size] ^= byte;
+    }
+
+    out
+}
+
+/// Base32-encode using nix's own alphabet and bit order
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity((bytes.len() * 8).div_ceil(5));
+
+    for n in (0..bytes.len() * 8).step_by(5).rev() {
+        let byte_index = n / 8;
+        let bit_index = n % 8;
+
+        let mut value = u16::from(bytes[byte_index]) >> bit_index;
+
+        if bit_index > 3 && byte_index + 1 < bytes.len() {
+            value |= u16::from(bytes[byte_index + 1]) << (8 - bit_index);
+        }
+
+        result.push(NIX_BASE32_ALPHABET[(value & 0x1f) as usize] as char);
+    }
+
+    result
+}
+
+/// Decode an SRI hash's base64 digest into lowercase hex, for use in nix's
+/// `fixed:out:` fingerprint. Falls back to the raw digest on decode failure,
+/// which only degrades the store-path prediction optimization rather than
+/// the correctness of the hash that gets computed
+fn sri_to_hex(sri_hash: &str) -> String {
+    let Some((_, digest_b64)) = sri_hash.split_once('-') else {
+        return sri_hash.to_string();
+    };
+
+    base64::engine::general_purpose::STANDARD
+        .decode(digest_b64)
+        .map(|bytes| hex_encode(&bytes))
+        .unwrap_or_else(|_| digest_b64.to_string())
+}
+
+/// Encode raw bytes as lowercase hex
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-256 of the empty string, as an SRI string. Used below to
+    /// check `predicted_store_path` against store paths nix itself produces
+    const EMPTY_SHA256_SRI: &str = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+
+    #[cfg(unix)]
+    #[test]
+    fn nar_sha256_dir_matches_a_known_good_hash() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+
+        std::fs::write(root.join("hello.txt"), "hello world\n").unwrap();
+
+        std::fs::write(root.join("run.sh"), "#!/bin/sh\necho hi\n").unwrap();
+        std::fs::set_permissions(root.join("run.sh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        std::os::unix::fs::symlink("hello.txt", root.join("link")).unwrap();
+
+        std::fs::create_dir(root.join("subdir")).unwrap();
+        std::fs::write(root.join("subdir").join("nested.txt"), "nested\n").unwrap();
+
+        assert_eq!(
+            nar_sha256_dir(root).unwrap(),
+            "sha256-LvmiaZNR+2RJ6SR1c9R3gS0aSD62v3T1kZon3Ca4lq4="
+        );
+    }
+
+    #[test]
+    fn predicted_store_path_matches_nixs_recursive_sha256_case() {
+        assert_eq!(
+            predicted_store_path("test", EMPTY_SHA256_SRI, true),
+            PathBuf::from("/nix/store/w7cimd7hrrnvmc5398vkp1q64h69349w-test")
+        );
+    }
+
+    #[test]
+    fn predicted_store_path_matches_nixs_flat_fixed_output_case() {
+        assert_eq!(
+            predicted_store_path("test", EMPTY_SHA256_SRI, false),
+            PathBuf::from("/nix/store/p6c1bd0dpj9i7abfavifd9rlqr01yazd-test")
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Per-scope npm registry resolution, parsed from `.npmrc`/`bunfig.toml`-style
+//! configuration entries (`@scope:registry=...` and `//host/:_authToken=...`)
+
+use std::collections::HashMap;
+
+/// # Registry Config
+///
+/// A parsed set of per-scope registry overrides and per-host auth tokens, as
+/// found in a project's `.npmrc` or `bunfig.toml`. This lets a scope such as
+/// `@mycorp` resolve to a private registry, optionally authenticated with a
+/// token
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RegistryConfig {
+    /// Scope (e.g. `@mycorp`) to registry base URL
+    scopes: HashMap<String, String>,
+    /// Registry host (e.g. `npm.pkg.github.com`) to auth token
+    auth_tokens: HashMap<String, String>,
+}
+
+impl RegistryConfig {
+    /// # Parse
+    ///
+    /// Parse `.npmrc`/`bunfig.toml`-style lines into a `RegistryConfig`.
+    /// Recognizes `@scope:registry=<url>` and `//<host>/:_authToken=<token>`
+    /// entries; any other line is ignored
+    ///
+    /// ## Usage
+    ///```rust
+    /// use bun2nix::package::RegistryConfig;
+    ///
+    /// let config = RegistryConfig::parse(
+    ///     "@mycorp:registry=https://npm.mycorp.dev/\n//npm.mycorp.dev/:_authToken=s3cr3t\n",
+    /// );
+    ///
+    /// assert_eq!(
+    ///     config.registry_for_scope("@mycorp"),
+    ///     Some("https://npm.mycorp.dev/")
+    /// );
+    /// assert_eq!(
+    ///     config.auth_token_for_host("npm.mycorp.dev"),
+    ///     Some("s3cr3t")
+    /// );
+    /// ```
+    pub fn parse(contents: &str) -> Self {
+        let mut config = Self::default();
+
+        for line in contents.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let key = key.trim();
+            let value = value.trim().trim_matches('"');
+
+            if let Some(scope) = key
+                .strip_suffix(":registry")
+                .and_then(|key| key.strip_prefix('@'))
+            {
+                config.scopes.insert(format!("@{scope}"), value.to_string());
+                continue;
+            }
+
+            if let Some(host) = key
+                .strip_prefix("//")
+                .and_then(|key| key.strip_suffix("/:_authToken"))
+            {
+                config.auth_tokens.insert(host.to_string(), value.to_string());
+            }
+        }
+
+        config
+    }
+
+    /// # Registry For Scope
+    ///
+    /// Look up the registry base URL configured for a given package scope
+    /// (e.g. `@mycorp`), if any
+    pub fn registry_for_scope(&self, scope: &str) -> Option<&str> {
+        self.scopes.get(scope).map(String::as_str)
+    }
+
+    /// # Auth Token For Host
+    ///
+    /// Look up the auth token configured for a given registry host, if any
+    pub fn auth_token_for_host(&self, host: &str) -> Option<&str> {
+        self.auth_tokens.get(host).map(String::as_str)
+    }
+}